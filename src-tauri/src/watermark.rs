@@ -1,8 +1,19 @@
 use serde::{Deserialize, Serialize};
 use image::{DynamicImage, Rgba};
-use imageproc::drawing::{draw_text_mut, text_size};
+use imageproc::drawing::text_size;
 use ab_glyph::{FontVec, PxScale};
 use anyhow::Result;
+use font_kit::family_name::FamilyName;
+use font_kit::handle::Handle;
+use font_kit::properties::{Properties, Style as FkStyle, Weight as FkWeight};
+use font_kit::source::SystemSource;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// `unicode_bidi` and `rustybuzz` back the complex-script shaping path in
+// `apply_text_watermark` (see `shape_with_bidi`); both are referenced only
+// at their call sites (a function-local `use` or a full path) to keep this
+// import block small.
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,6 +70,10 @@ pub struct TextWatermarkSettings {
     pub shadow_color: [u8; 4], // RGBA
     pub shadow_offset_x: i32,
     pub shadow_offset_y: i32,
+    /// Wrap lines once their width would exceed this fraction of the image
+    /// width (e.g. 0.8 wraps at 80% of the frame). `None` disables word
+    /// wrapping; explicit `\n` in `text` still starts a new line either way.
+    pub max_width_fraction: Option<f32>,
 }
 
 impl Default for WatermarkSettings {
@@ -85,6 +100,7 @@ impl Default for WatermarkSettings {
                 shadow_color: [0, 0, 0, 128],
                 shadow_offset_x: 1,
                 shadow_offset_y: 1,
+                max_width_fraction: Some(0.9),
             }),
             image_path: None,
         }
@@ -160,89 +176,613 @@ pub fn replace_metadata_placeholders(
 
 // Rendering
 
+/// Slant a resolved font face was matched against. Mirrors the subset of
+/// `font_kit::properties::Style` we care about for selection purposes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+}
+
+/// Cache key for a resolved face: family name plus the weight/style it was
+/// resolved for, so "Arial" regular and "Arial" bold are cached separately.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FontKey {
+    family: String,
+    weight: u16,
+    style: FontStyle,
+}
+
+/// Whether a cached face genuinely matches the requested weight and/or
+/// style, tracked per attribute rather than as one combined flag. A family
+/// can have a genuine Bold face but no Bold Italic, in which case a
+/// bold+italic request resolves to that Bold face with `weight_genuine:
+/// true, style_genuine: false` - only the italic half needs synthesizing,
+/// not bold on top of an already-bold face.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceMatch {
+    weight_genuine: bool,
+    style_genuine: bool,
+}
+
+impl FaceMatch {
+    const REGULAR_FALLBACK: FaceMatch = FaceMatch { weight_genuine: false, style_genuine: false };
+}
+
+/// Styling to synthesize on top of a face that doesn't genuinely have the
+/// requested weight/slant - bold and italic are decided independently from
+/// `FaceMatch`'s `weight_genuine`/`style_genuine`, and only applied to
+/// glyphs drawn from the primary face (fallback-chain faces used for glyph
+/// coverage are left alone).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct SyntheticStyle {
+    bold: bool,
+    italic: bool,
+}
+
+/// A loaded face kept in both forms we need: the parsed `FontVec` for
+/// measuring/rasterizing glyphs via `ab_glyph`, and the raw font bytes for
+/// shaping the same face with `rustybuzz` (which wants its own view of the
+/// font tables rather than `ab_glyph`'s parsed outlines).
+struct LoadedFace {
+    font: FontVec,
+    data: Arc<Vec<u8>>,
+}
+
+/// A single positioned glyph, already resolved to the face that covers it
+/// and shaped (advances/offsets from HarfBuzz, in pixels).
+struct ShapedGlyph {
+    face_idx: usize,
+    glyph_id: ab_glyph::GlyphId,
+    x_advance: f32,
+    x_offset: f32,
+    y_offset: f32,
+}
+
+/// A wrapped line's layout (segmented runs or shaped glyphs), computed once
+/// per line and reused for width measurement and both the shadow and main
+/// draw passes, so none of them can shape or segment the text differently.
+enum LineLayout<'a> {
+    Ascii(Vec<(usize, &'a str)>),
+    Shaped(Vec<ShapedGlyph>),
+}
+
+/// sRGB <-> linear-light lookup tables, used so glyph/logo coverage is
+/// blended against the background in linear space instead of sRGB (which
+/// otherwise darkens antialiased edges and under-weights shadows).
+struct GammaLut {
+    to_linear: [f32; 256],
+    to_srgb: [u8; 256],
+}
+
+impl GammaLut {
+    fn new() -> Self {
+        let mut to_linear = [0.0f32; 256];
+        for (i, entry) in to_linear.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            *entry = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+        }
+
+        let mut to_srgb = [0u8; 256];
+        for (i, entry) in to_srgb.iter_mut().enumerate() {
+            let c = i as f32 / 255.0;
+            let s = if c <= 0.003_130_8 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+            *entry = (s * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+
+        Self { to_linear, to_srgb }
+    }
+
+    fn srgb_to_linear(&self, channel: u8) -> f32 {
+        self.to_linear[channel as usize]
+    }
+
+    /// `linear` is quantized to 256 steps before the lookup, which is coarse
+    /// near black: linear levels 0 and 1/255 map to sRGB 0 and 13.
+    fn linear_to_srgb(&self, linear: f32) -> u8 {
+        let index = (linear.clamp(0.0, 1.0) * 255.0).round() as usize;
+        self.to_srgb[index]
+    }
+}
+
+fn gamma_lut() -> &'static GammaLut {
+    static LUT: std::sync::OnceLock<GammaLut> = std::sync::OnceLock::new();
+    LUT.get_or_init(GammaLut::new)
+}
+
 pub struct WatermarkRenderer {
-    fonts: std::collections::HashMap<String, FontVec>,
+    font_source: SystemSource,
+    font_cache: Mutex<HashMap<FontKey, (Arc<LoadedFace>, FaceMatch)>>,
+    /// Faces tried, in order, for any grapheme the requested family can't
+    /// render itself - CJK faces, Arabic-script faces, emoji/symbol faces,
+    /// then generic sans faces (whichever of them are installed).
+    fallback_faces: Vec<Arc<LoadedFace>>,
 }
 
 impl WatermarkRenderer {
     pub fn new() -> Result<Self> {
-        let mut fonts = std::collections::HashMap::new();
-        
-        // Try to load system fonts, but don't fail if none are found for now
-        if let Err(e) = Self::try_load_system_fonts(&mut fonts) {
-            println!("Warning: Failed to load system fonts: {}", e);
-            // Create an empty renderer for now - we can handle this better later
+        let font_source = SystemSource::new();
+        let fallback_faces = Self::load_fallback_faces(&font_source);
+        Ok(Self {
+            font_source,
+            font_cache: Mutex::new(HashMap::new()),
+            fallback_faces,
+        })
+    }
+
+    /// Best-effort chain of faces used to cover codepoints the requested
+    /// family has no glyph for (CJK ideographs, Arabic/Hebrew script, emoji,
+    /// unusual diacritics) - covering scripts the bidi/shaping path in
+    /// `shape_with_bidi` is meant to handle is as important here as covering
+    /// the glyphs themselves. Families that aren't installed are silently
+    /// skipped - coverage just degrades rather than failing the whole
+    /// watermark.
+    ///
+    /// The color emoji families are kept in the chain for the rare glyphs
+    /// they ship as plain outlines (some symbol/dingbat ranges), but
+    /// `face_covers_grapheme` will correctly pass over them for anything
+    /// stored as a color/bitmap table, since `draw_glyph` has no rasterizer
+    /// for those - genuine color emoji rendering is not supported yet.
+    fn load_fallback_faces(source: &SystemSource) -> Vec<Arc<LoadedFace>> {
+        const FALLBACK_FAMILIES: &[&str] = &[
+            "Noto Sans CJK SC",
+            "Noto Sans CJK JP",
+            "Microsoft YaHei",
+            "PingFang SC",
+            "Noto Sans Arabic",
+            "Noto Naskh Arabic",
+            "Geeza Pro",
+            "Tahoma",
+            "Noto Color Emoji",
+            "Apple Color Emoji",
+            "Segoe UI Emoji",
+            "Noto Sans",
+            "Segoe UI",
+            "Helvetica",
+        ];
+
+        FALLBACK_FAMILIES
+            .iter()
+            .filter_map(|family| {
+                let handle = source.select_family_by_name(family).ok()?.fonts().first()?.clone();
+                Self::loaded_face_from_handle(&handle).ok()
+            })
+            .map(Arc::new)
+            .collect()
+    }
+
+    /// Resolves `family` to a loaded face matching the requested weight and
+    /// slant as closely as possible, scanning the platform's installed fonts
+    /// rather than a hardcoded path list. Results are cached by
+    /// (family, weight, style) so repeated exports reuse the same face.
+    fn resolve_font(&self, family: &str, bold: bool, italic: bool) -> Result<(Arc<LoadedFace>, FaceMatch)> {
+        let weight = if bold { FkWeight::BOLD.0 as u16 } else { FkWeight::NORMAL.0 as u16 };
+        let style = if italic { FontStyle::Italic } else { FontStyle::Normal };
+        let key = FontKey { family: family.to_string(), weight, style };
+
+        if let Some(cached) = self.font_cache.lock().unwrap().get(&key) {
+            return Ok(cached.clone());
         }
-        
-        Ok(Self { fonts })
+
+        let resolved = self.load_best_matching_face(family, weight, style)?;
+        self.font_cache.lock().unwrap().insert(key, resolved.clone());
+        Ok(resolved)
     }
 
-        fn try_load_system_fonts(fonts: &mut std::collections::HashMap<String, FontVec>) -> Result<()> {
-        // Try to load common system fonts
-        let font_paths = Self::get_system_font_paths();
-        
-        for (name, paths) in font_paths {
-            for path in paths {
-                if let Ok(font_data) = std::fs::read(&path) {
-                    if let Ok(font) = FontVec::try_from_vec(font_data) {
-                        fonts.insert(name.clone(), font);
-                        break;
-                    }
+    /// Enumerates every face the system has for `family`, scores each by how
+    /// far its (weight, style) is from what was requested, and keeps the
+    /// closest one. Falls back to the family's best match under font-kit's
+    /// own matching (or a generic sans-serif) if the family isn't installed.
+    fn load_best_matching_face(
+        &self,
+        family: &str,
+        desired_weight: u16,
+        desired_style: FontStyle,
+    ) -> Result<(Arc<LoadedFace>, FaceMatch)> {
+        let handles = self
+            .font_source
+            .select_family_by_name(family)
+            .map(|f| f.fonts().to_vec())
+            .unwrap_or_default();
+
+        let mut best: Option<(f32, Handle, Properties)> = None;
+        for handle in handles {
+            let Ok(font) = handle.load() else { continue };
+            let props = font.properties();
+            let distance = Self::style_distance(&props, desired_weight, desired_style);
+            if best.as_ref().map_or(true, |(d, ..)| distance < *d) {
+                best = Some((distance, handle, props));
+            }
+        }
+
+        let Some((_, handle, props)) = best else {
+            return self.load_fallback_face(family);
+        };
+
+        let loaded = Self::loaded_face_from_handle(&handle)?;
+        let weight_genuine = (props.weight.0 - desired_weight as f32).abs() <= 50.0;
+        let style_genuine = Self::style_matches(props.style, desired_style);
+        Ok((Arc::new(loaded), FaceMatch { weight_genuine, style_genuine }))
+    }
+
+    fn style_distance(props: &Properties, desired_weight: u16, desired_style: FontStyle) -> f32 {
+        let weight_delta = (props.weight.0 - desired_weight as f32).abs();
+        let style_mismatch = if Self::style_matches(props.style, desired_style) { 0.0 } else { 200.0 };
+        weight_delta + style_mismatch
+    }
+
+    fn style_matches(actual: FkStyle, desired: FontStyle) -> bool {
+        match desired {
+            FontStyle::Normal => actual == FkStyle::Normal,
+            FontStyle::Italic => matches!(actual, FkStyle::Italic | FkStyle::Oblique),
+        }
+    }
+
+    fn loaded_face_from_handle(handle: &Handle) -> Result<LoadedFace> {
+        let data: Vec<u8> = match handle {
+            Handle::Path { path, .. } => std::fs::read(path)?,
+            Handle::Memory { bytes, .. } => (**bytes).clone(),
+        };
+        let font = FontVec::try_from_vec(data.clone())
+            .map_err(|e| anyhow::anyhow!("failed to parse font data: {:?}", e))?;
+        Ok(LoadedFace { font, data: Arc::new(data) })
+    }
+
+    /// No face for `family` at all (not installed) — ask font-kit for its
+    /// closest system match, falling back to a generic sans-serif.
+    fn load_fallback_face(&self, family: &str) -> Result<(Arc<LoadedFace>, FaceMatch)> {
+        let handle = self
+            .font_source
+            .select_best_match(
+                &[FamilyName::Title(family.to_string()), FamilyName::SansSerif],
+                &Properties::new(),
+            )
+            .map_err(|e| anyhow::anyhow!("no fallback font available for '{}': {}", family, e))?;
+        let loaded = Self::loaded_face_from_handle(&handle)?;
+        Ok((Arc::new(loaded), FaceMatch::REGULAR_FALLBACK))
+    }
+
+    /// A face "covers" a grapheme only if every character in it both has a
+    /// cmap entry *and* a rasterizable outline. `outline_glyph` (used by
+    /// `draw_glyph`) only draws vector outlines (`glyf`/CFF) - color/bitmap
+    /// glyph formats (CBDT/CBLC, sbix, COLR/CPAL), the tables emoji fonts
+    /// ship, have a cmap entry but no outline, so checking cmap presence
+    /// alone would route text to a face that then silently fails to draw
+    /// anything. Checking the outline here means segmentation instead falls
+    /// through to the next fallback face (or, when no face covers the
+    /// grapheme, to the last face in the chain - see `segment_by_face`), at
+    /// the cost of not rendering color emoji at all.
+    fn face_covers_grapheme(face: &LoadedFace, grapheme: &str) -> bool {
+        use ab_glyph::Font;
+        grapheme.chars().all(|c| {
+            let id = face.font.glyph_id(c);
+            id.0 != 0 && face.font.outline(id).is_some()
+        })
+    }
+
+    /// Segments `text` into (face_index, run) pairs covering the whole
+    /// string, coalescing consecutive graphemes that resolve to the same
+    /// face. `faces[0]` (the requested family) is always tried first; later
+    /// entries are the fallback chain, and the last face wins by default if
+    /// nothing covers a grapheme.
+    fn segment_by_face<'a>(faces: &[Arc<LoadedFace>], text: &'a str) -> Vec<(usize, &'a str)> {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let mut runs: Vec<(usize, &'a str)> = Vec::new();
+        let mut run_start = 0usize;
+        let mut run_face: Option<usize> = None;
+        let mut last_end = 0usize;
+
+        for (start, grapheme) in text.grapheme_indices(true) {
+            let face_idx = faces
+                .iter()
+                .position(|face| Self::face_covers_grapheme(face, grapheme))
+                .unwrap_or(faces.len() - 1);
+
+            match run_face {
+                Some(current) if current == face_idx => {}
+                Some(current) => {
+                    runs.push((current, &text[run_start..start]));
+                    run_start = start;
+                    run_face = Some(face_idx);
                 }
+                None => run_face = Some(face_idx),
             }
+            last_end = start + grapheme.len();
         }
-        
-        // If no system fonts found, create a minimal fallback
-        if fonts.is_empty() {
-            // Use a very basic built-in font approach or return an error
-            return Err(anyhow::anyhow!("No suitable fonts found on system"));
+
+        if let Some(face_idx) = run_face {
+            runs.push((face_idx, &text[run_start..last_end]));
         }
-        
-        Ok(())
+
+        runs
     }
-    
-    fn get_system_font_paths() -> Vec<(String, Vec<String>)> {
-        let mut paths = Vec::new();
-        
-        #[cfg(target_os = "windows")]
-        {
-            paths.push(("Arial".to_string(), vec![
-                "C:/Windows/Fonts/arial.ttf".to_string(),
-                "C:/Windows/Fonts/Arial.ttf".to_string(),
-            ]));
-            paths.push(("Default".to_string(), vec![
-                "C:/Windows/Fonts/calibri.ttf".to_string(),
-                "C:/Windows/Fonts/tahoma.ttf".to_string(),
-            ]));
+
+    fn measure_runs(faces: &[Arc<LoadedFace>], scale: PxScale, runs: &[(usize, &str)]) -> (i32, i32) {
+        let mut width = 0i32;
+        let mut height = 0i32;
+        for (face_idx, run) in runs {
+            let (run_width, run_height) = text_size(scale, &faces[*face_idx].font, run);
+            width += run_width as i32;
+            height = height.max(run_height as i32);
         }
-        
-        #[cfg(target_os = "macos")]
-        {
-            paths.push(("Arial".to_string(), vec![
-                "/System/Library/Fonts/Arial.ttf".to_string(),
-                "/Library/Fonts/Arial.ttf".to_string(),
-            ]));
-            paths.push(("Default".to_string(), vec![
-                "/System/Library/Fonts/Helvetica.ttc".to_string(),
-                "/System/Library/Fonts/Geneva.ttf".to_string(),
-            ]));
+        (width, height)
+    }
+
+    /// Blends one covered pixel in linear light and writes it back through
+    /// the gamma LUT. `color`'s alpha channel already folds in the caller's
+    /// opacity; `coverage` is the glyph/logo's own per-pixel alpha (0..1),
+    /// and the two are multiplied together as the premultiplied blend factor.
+    fn composite_coverage(image: &mut DynamicImage, x: u32, y: u32, color: Rgba<u8>, coverage: f32) {
+        use image::GenericImage;
+
+        let alpha = (coverage * (color[3] as f32 / 255.0)).clamp(0.0, 1.0);
+        if alpha <= 0.0 {
+            return;
         }
-        
-        #[cfg(target_os = "linux")]
-        {
-            paths.push(("Arial".to_string(), vec![
-                "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf".to_string(),
-                "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".to_string(),
-            ]));
-            paths.push(("Default".to_string(), vec![
-                "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf".to_string(),
-                "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf".to_string(),
-            ]));
+
+        let lut = gamma_lut();
+        let dst = image.get_pixel(x, y);
+        let mut out = [0u8; 4];
+        for c in 0..3 {
+            let src_linear = lut.srgb_to_linear(color[c]);
+            let dst_linear = lut.srgb_to_linear(dst[c]);
+            out[c] = lut.linear_to_srgb(src_linear * alpha + dst_linear * (1.0 - alpha));
         }
-        
-        paths
+        let dst_alpha = dst[3] as f32 / 255.0;
+        out[3] = ((alpha + dst_alpha * (1.0 - alpha)) * 255.0).round().clamp(0.0, 255.0) as u8;
+
+        image.put_pixel(x, y, Rgba(out));
     }
-    
+
+    /// How far the horizontal shear at image row `image_y` displaces a pixel
+    /// relative to `baseline_y`, approximating a ~12 degree oblique slant.
+    fn italic_shear_offset(baseline_y: f32, image_y: f32) -> f32 {
+        const ITALIC_SLANT_DEGREES: f32 = 12.0;
+        (baseline_y - image_y) * ITALIC_SLANT_DEGREES.to_radians().tan()
+    }
+
+    /// Rasterizes and composites a single glyph, optionally synthesizing
+    /// bold and/or italic when the resolved face doesn't genuinely have
+    /// them. Synthetic bold redraws the glyph at small +x/+y offsets (1.8% of
+    /// the pixel size, at least half a pixel) and takes the per-pixel
+    /// coverage union (an emboss/multistrike, which thickens stems without a
+    /// bold face); synthetic italic shears each rasterized scanline
+    /// horizontally based on its distance from the baseline - the shear is
+    /// applied to the pixel coverage after rasterizing, not to the outline
+    /// points.
+    fn draw_glyph(
+        image: &mut DynamicImage,
+        font: &FontVec,
+        glyph_id: ab_glyph::GlyphId,
+        pen_x: f32,
+        baseline_y: f32,
+        scale: PxScale,
+        color: Rgba<u8>,
+        synth: SyntheticStyle,
+    ) {
+        use ab_glyph::Font;
+
+        let strike_offsets: Vec<(f32, f32)> = if synth.bold {
+            let strike = (scale.x * 0.018).max(0.5);
+            vec![(0.0, 0.0), (strike, 0.0), (0.0, strike), (strike, strike)]
+        } else {
+            vec![(0.0, 0.0)]
+        };
+
+        let mut coverage: HashMap<(i32, i32), f32> = HashMap::new();
+        for (dx, dy) in &strike_offsets {
+            let positioned = glyph_id.with_scale_and_position(scale, ab_glyph::point(pen_x + dx, baseline_y + dy));
+            // `outline_glyph` returns `None` for a glyph with no vector
+            // outline (color/bitmap-only glyphs). `face_covers_grapheme`
+            // keeps most of those out, but shaped glyph ids are not checked
+            // again, so such a glyph is skipped here rather than drawn.
+            let Some(outline) = font.outline_glyph(positioned) else { continue };
+            let bounds = outline.px_bounds();
+            outline.draw(|px, py, c| {
+                if c <= 0.0 {
+                    return;
+                }
+                let mut image_x = bounds.min.x as i32 + px as i32;
+                let image_y = bounds.min.y as i32 + py as i32;
+                if synth.italic {
+                    image_x += Self::italic_shear_offset(baseline_y, image_y as f32).round() as i32;
+                }
+                let entry = coverage.entry((image_x, image_y)).or_insert(0.0);
+                *entry = entry.max(c);
+            });
+        }
+
+        for ((image_x, image_y), c) in coverage {
+            if image_x < 0 || image_y < 0 || image_x as u32 >= image.width() || image_y as u32 >= image.height() {
+                continue;
+            }
+            Self::composite_coverage(image, image_x as u32, image_y as u32, color, c);
+        }
+    }
+
+    /// Rasterizes one run's glyph outlines directly and composites them
+    /// through `composite_coverage`, rather than `draw_text_mut`'s direct
+    /// sRGB write, so antialiased edges blend in linear light.
+    fn draw_glyph_run(
+        image: &mut DynamicImage,
+        font: &FontVec,
+        text: &str,
+        color: Rgba<u8>,
+        x: i32,
+        y: i32,
+        scale: PxScale,
+        synth: SyntheticStyle,
+    ) {
+        use ab_glyph::{Font, ScaleFont};
+
+        let scaled_font = font.as_scaled(scale);
+        let baseline_y = y as f32 + scaled_font.ascent();
+        let mut pen_x = x as f32;
+
+        for ch in text.chars() {
+            let glyph_id = font.glyph_id(ch);
+            Self::draw_glyph(image, font, glyph_id, pen_x, baseline_y, scale, color, synth);
+            pen_x += scaled_font.h_advance(glyph_id);
+        }
+    }
+
+    /// Draws `runs` left-to-right starting at `(x, y)`, advancing the pen by
+    /// each run's measured width so scripts can mix within one caption. This
+    /// is the fast path for pure-ASCII text, which never needs shaping or
+    /// bidi reordering. `synth` (bold/italic synthesis) only applies to runs
+    /// drawn from the primary face (index 0) - fallback-chain faces used
+    /// purely for glyph coverage are left alone.
+    fn draw_runs(
+        image: &mut DynamicImage,
+        faces: &[Arc<LoadedFace>],
+        runs: &[(usize, &str)],
+        color: Rgba<u8>,
+        x: i32,
+        y: i32,
+        scale: PxScale,
+        synth: SyntheticStyle,
+    ) {
+        let mut pen_x = x;
+        for (face_idx, run) in runs {
+            let font = &faces[*face_idx].font;
+            let run_synth = if *face_idx == 0 { synth } else { SyntheticStyle::default() };
+            Self::draw_glyph_run(image, font, run, color, pen_x, y, scale, run_synth);
+            let (run_width, _) = text_size(scale, font, run);
+            pen_x += run_width as i32;
+        }
+    }
+
+    /// Full shaping path for complex scripts and mixed-direction text: runs
+    /// the string through the Unicode bidi algorithm to get visually-ordered
+    /// directional runs, segments each run by face coverage (reusing
+    /// `segment_by_face`), then shapes each sub-run with HarfBuzz so joining
+    /// forms (Arabic), reordered matras (Indic), and RTL runs come out
+    /// correct. This is itself stateless - callers that need the result for
+    /// both measurement and drawing (see `layout_line`) must call it once and
+    /// reuse the returned glyphs, rather than re-shaping per use, so the
+    /// shadow pass and main text stay pixel-aligned.
+    fn shape_with_bidi(faces: &[Arc<LoadedFace>], text: &str, scale: PxScale) -> Vec<ShapedGlyph> {
+        use unicode_bidi::BidiInfo;
+
+        let bidi_info = BidiInfo::new(text, None);
+        let mut glyphs = Vec::new();
+
+        for para in &bidi_info.paragraphs {
+            let line = para.range.clone();
+            let (levels, runs) = bidi_info.visual_runs(para, line);
+            for run in runs {
+                let run_text = &text[run.clone()];
+                let rtl = levels[run.start].is_rtl();
+                let sub_runs = Self::segment_by_face(faces, run_text);
+                // `segment_by_face` always walks left-to-right in logical
+                // (string) order. For an LTR run that's also the correct
+                // visual order, but for RTL the first sub-run in the string
+                // is the rightmost one visually, so the sub-run order itself
+                // - not just each sub-run's own shaping - needs reversing.
+                if rtl {
+                    for (face_idx, sub_run) in sub_runs.into_iter().rev() {
+                        glyphs.extend(Self::shape_run(faces, face_idx, sub_run, rtl, scale));
+                    }
+                } else {
+                    for (face_idx, sub_run) in sub_runs {
+                        glyphs.extend(Self::shape_run(faces, face_idx, sub_run, rtl, scale));
+                    }
+                }
+            }
+        }
+
+        glyphs
+    }
+
+    fn shape_run(
+        faces: &[Arc<LoadedFace>],
+        face_idx: usize,
+        text: &str,
+        rtl: bool,
+        scale: PxScale,
+    ) -> Vec<ShapedGlyph> {
+        let Some(hb_face) = rustybuzz::Face::from_slice(&faces[face_idx].data, 0) else {
+            return Vec::new();
+        };
+
+        let mut buffer = rustybuzz::UnicodeBuffer::new();
+        buffer.push_str(text);
+        buffer.set_direction(if rtl { rustybuzz::Direction::RightToLeft } else { rustybuzz::Direction::LeftToRight });
+        buffer.guess_segment_properties();
+
+        let output = rustybuzz::shape(&hb_face, &[], buffer);
+        let units_per_em = hb_face.units_per_em() as f32;
+        let font_scale = scale.x / units_per_em;
+
+        output
+            .glyph_infos()
+            .iter()
+            .zip(output.glyph_positions())
+            .map(|(info, pos)| ShapedGlyph {
+                face_idx,
+                glyph_id: ab_glyph::GlyphId(info.glyph_id as u16),
+                x_advance: pos.x_advance as f32 * font_scale,
+                x_offset: pos.x_offset as f32 * font_scale,
+                y_offset: pos.y_offset as f32 * font_scale,
+            })
+            .collect()
+    }
+
+    fn measure_shaped(faces: &[Arc<LoadedFace>], glyphs: &[ShapedGlyph], scale: PxScale) -> (i32, i32) {
+        use ab_glyph::{Font, ScaleFont};
+
+        let width: f32 = glyphs.iter().map(|g| g.x_advance).sum();
+        let height = faces
+            .iter()
+            .map(|face| {
+                let scaled = face.font.as_scaled(scale);
+                scaled.ascent() - scaled.descent()
+            })
+            .fold(0.0_f32, f32::max);
+
+        (width.round() as i32, height.round() as i32)
+    }
+
+    /// Rasterizes shaped glyph outlines directly (rather than `draw_text_mut`,
+    /// which only knows plain strings) and composites each one at its shaped
+    /// pen position.
+    /// `synth` only applies to glyphs shaped from the primary face (index
+    /// 0) - see `draw_runs` for why the rest of the fallback chain is left
+    /// alone.
+    fn draw_shaped_glyphs(
+        image: &mut DynamicImage,
+        faces: &[Arc<LoadedFace>],
+        glyphs: &[ShapedGlyph],
+        color: Rgba<u8>,
+        x: i32,
+        y: i32,
+        scale: PxScale,
+        synth: SyntheticStyle,
+    ) {
+        use ab_glyph::{Font, ScaleFont};
+
+        let mut pen_x = x as f32;
+
+        for glyph in glyphs {
+            let font = &faces[glyph.face_idx].font;
+            let baseline_y = y as f32 + font.as_scaled(scale).ascent();
+            let glyph_synth = if glyph.face_idx == 0 { synth } else { SyntheticStyle::default() };
+            Self::draw_glyph(
+                image,
+                font,
+                glyph.glyph_id,
+                pen_x + glyph.x_offset,
+                baseline_y + glyph.y_offset,
+                scale,
+                color,
+                glyph_synth,
+            );
+
+            pen_x += glyph.x_advance;
+        }
+    }
+
     pub fn apply_watermark(
         &self,
         mut image: DynamicImage,
@@ -260,12 +800,9 @@ impl WatermarkRenderer {
             WatermarkType::Text => {
                 if let Some(text_settings) = &settings.text_settings {
                     println!("Applying text watermark with text: '{}'", text_settings.text);
-                    // Skip text watermarks if no fonts are loaded
-                    if self.fonts.is_empty() {
-                        println!("Warning: No fonts loaded, skipping text watermark");
-                        return Ok(image);
+                    if let Err(e) = self.apply_text_watermark(&mut image, settings, text_settings, metadata, filename) {
+                        println!("Warning: Failed to apply text watermark: {}", e);
                     }
-                    self.apply_text_watermark(&mut image, settings, text_settings, metadata, filename)?;
                 } else {
                     println!("Warning: Text watermark enabled but no text settings provided");
                 }
@@ -296,55 +833,217 @@ impl WatermarkRenderer {
             return Ok(());
         }
         
-        let font = self.fonts.get(&text_settings.font_family)
-            .or_else(|| self.fonts.get("Arial"))
-            .ok_or_else(|| anyhow::anyhow!("No suitable font found"))?;
-        
+        let (primary_font, face_match) = self.resolve_font(&text_settings.font_family, text_settings.bold, text_settings.italic)?;
+        let synth = SyntheticStyle {
+            bold: text_settings.bold && !face_match.weight_genuine,
+            italic: text_settings.italic && !face_match.style_genuine,
+        };
+        let mut faces = Vec::with_capacity(1 + self.fallback_faces.len());
+        faces.push(primary_font);
+        faces.extend(self.fallback_faces.iter().cloned());
+
         let scale = PxScale::from(text_settings.font_size * settings.scale);
         let color = Rgba([
             text_settings.color[0],
-            text_settings.color[1], 
+            text_settings.color[1],
             text_settings.color[2],
             (text_settings.color[3] as f32 * settings.opacity) as u8,
         ]);
-        
-        // Calculate text dimensions
-        let (text_width, text_height) = text_size(scale, font, &text);
-        
-        // Calculate position
-        let (x, y) = self.calculate_text_position(
+
+        let shadow_color = Rgba([
+            text_settings.shadow_color[0],
+            text_settings.shadow_color[1],
+            text_settings.shadow_color[2],
+            (text_settings.shadow_color[3] as f32 * settings.opacity) as u8,
+        ]);
+
+        let max_width = text_settings
+            .max_width_fraction
+            .map(|fraction| fraction * image.width() as f32);
+        let lines = Self::wrap_lines(&faces, &text, scale, max_width);
+        let line_advance = Self::line_advance(&faces, scale);
+        let block_height = (line_advance * lines.len() as f32).round() as i32;
+
+        // Vertical alignment anchors the whole wrapped block; horizontal
+        // alignment is still per-line so centered/right-aligned captions
+        // hug each line's own width rather than the widest one.
+        let (_, base_y) = self.calculate_text_position(
             image.width() as i32,
             image.height() as i32,
-            text_width as i32,
-            text_height as i32,
+            0,
+            block_height,
             &settings.position,
         );
-        
-        // Draw shadow if enabled
-        if text_settings.shadow {
-            let shadow_color = Rgba([
-                text_settings.shadow_color[0],
-                text_settings.shadow_color[1],
-                text_settings.shadow_color[2],
-                (text_settings.shadow_color[3] as f32 * settings.opacity) as u8,
-            ]);
-            
-            draw_text_mut(
-                image,
-                shadow_color,
-                x + text_settings.shadow_offset_x,
-                y + text_settings.shadow_offset_y,
-                scale,
-                font,
-                &text,
+
+        for (i, line) in lines.iter().enumerate() {
+            // Shaped/segmented once and reused below for the width
+            // measurement and both draw passes, so the shadow and main text
+            // can't drift out of alignment from re-shaping independently.
+            let layout = Self::layout_line(&faces, line, scale);
+            let (line_width, _) = Self::measure_layout(&faces, scale, &layout);
+            let (line_x, _) = self.calculate_text_position(
+                image.width() as i32,
+                image.height() as i32,
+                line_width,
+                block_height,
+                &settings.position,
             );
+            let line_y = base_y + (line_advance * i as f32).round() as i32;
+
+            if text_settings.shadow {
+                Self::draw_layout(
+                    image,
+                    &faces,
+                    &layout,
+                    shadow_color,
+                    line_x + text_settings.shadow_offset_x,
+                    line_y + text_settings.shadow_offset_y,
+                    scale,
+                    synth,
+                );
+            }
+            Self::draw_layout(image, &faces, &layout, color, line_x, line_y, scale, synth);
         }
-        
-        // Draw main text
-        draw_text_mut(image, color, x, y, scale, font, &text);
-        
+
         Ok(())
     }
+
+    /// Segments or shapes `line` once - ASCII takes the fast run-segmentation
+    /// path, anything else goes through full bidi/HarfBuzz shaping. Callers
+    /// that need both a width and a drawn result (the per-line render loop in
+    /// `apply_text_watermark`) should compute this once and pass it to both
+    /// `measure_layout` and `draw_layout` rather than redoing the
+    /// segmentation/shaping per call.
+    fn layout_line<'a>(faces: &[Arc<LoadedFace>], line: &'a str, scale: PxScale) -> LineLayout<'a> {
+        if line.is_ascii() {
+            LineLayout::Ascii(Self::segment_by_face(faces, line))
+        } else {
+            LineLayout::Shaped(Self::shape_with_bidi(faces, line, scale))
+        }
+    }
+
+    fn measure_layout(faces: &[Arc<LoadedFace>], scale: PxScale, layout: &LineLayout) -> (i32, i32) {
+        match layout {
+            LineLayout::Ascii(runs) => Self::measure_runs(faces, scale, runs),
+            LineLayout::Shaped(glyphs) => Self::measure_shaped(faces, glyphs, scale),
+        }
+    }
+
+    fn draw_layout(
+        image: &mut DynamicImage,
+        faces: &[Arc<LoadedFace>],
+        layout: &LineLayout,
+        color: Rgba<u8>,
+        x: i32,
+        y: i32,
+        scale: PxScale,
+        synth: SyntheticStyle,
+    ) {
+        match layout {
+            LineLayout::Ascii(runs) => Self::draw_runs(image, faces, runs, color, x, y, scale, synth),
+            LineLayout::Shaped(glyphs) => Self::draw_shaped_glyphs(image, faces, glyphs, color, x, y, scale, synth),
+        }
+    }
+
+    /// Measures `line`'s width via `layout_line`, for callers (word wrapping)
+    /// that only need a width and don't also need to draw the same layout.
+    fn measure_line(faces: &[Arc<LoadedFace>], scale: PxScale, line: &str) -> (i32, i32) {
+        let layout = Self::layout_line(faces, line, scale);
+        Self::measure_layout(faces, scale, &layout)
+    }
+
+    /// Line-height advance (ascent - descent + line gap) taken from the
+    /// primary face, used to stack wrapped lines and to size the block for
+    /// vertical alignment.
+    fn line_advance(faces: &[Arc<LoadedFace>], scale: PxScale) -> f32 {
+        use ab_glyph::{Font, ScaleFont};
+
+        faces
+            .first()
+            .map(|face| {
+                let scaled = face.font.as_scaled(scale);
+                scaled.height() + scaled.line_gap()
+            })
+            .unwrap_or(scale.y)
+    }
+
+    /// Splits `text` into display lines: hard breaks on explicit `\n`, then -
+    /// if `max_width` is set - greedily word-wraps each paragraph so no line
+    /// exceeds it, hard-breaking by character when a single word alone
+    /// overflows.
+    fn wrap_lines(faces: &[Arc<LoadedFace>], text: &str, scale: PxScale, max_width: Option<f32>) -> Vec<String> {
+        let measure = |s: &str| Self::measure_line(faces, scale, s).0;
+        text.split('\n')
+            .flat_map(|paragraph| Self::wrap_paragraph(paragraph, max_width, &measure))
+            .collect()
+    }
+
+    /// Greedily word-wraps `paragraph` so no line measures wider than
+    /// `max_width`, hard-breaking by character (via `hard_break_word`) when a
+    /// single word alone overflows it. `measure` is injected rather than
+    /// measuring against a loaded face directly so this pure wrapping logic
+    /// can be unit-tested without a real font.
+    fn wrap_paragraph(paragraph: &str, max_width: Option<f32>, measure: &impl Fn(&str) -> i32) -> Vec<String> {
+        let Some(max_width) = max_width else {
+            return vec![paragraph.to_string()];
+        };
+        if paragraph.is_empty() {
+            return vec![String::new()];
+        }
+
+        let mut lines = Vec::new();
+        let mut current = String::new();
+
+        for word in paragraph.split_whitespace() {
+            let candidate = if current.is_empty() { word.to_string() } else { format!("{current} {word}") };
+            if measure(&candidate) as f32 <= max_width {
+                current = candidate;
+                continue;
+            }
+
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+            }
+
+            if measure(word) as f32 <= max_width {
+                current = word.to_string();
+            } else {
+                lines.extend(Self::hard_break_word(word, max_width, measure));
+            }
+        }
+
+        if !current.is_empty() {
+            lines.push(current);
+        }
+        if lines.is_empty() {
+            lines.push(String::new());
+        }
+
+        lines
+    }
+
+    /// Breaks a single word that's wider than `max_width` on its own,
+    /// character by character, since there's no word boundary left to wrap
+    /// at. Takes the same injected `measure` as `wrap_paragraph`, for the
+    /// same testability reason.
+    fn hard_break_word(word: &str, max_width: f32, measure: &impl Fn(&str) -> i32) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut current = String::new();
+
+        for ch in word.chars() {
+            current.push(ch);
+            if measure(&current) as f32 > max_width && current.chars().count() > 1 {
+                current.pop();
+                lines.push(std::mem::take(&mut current));
+                current.push(ch);
+            }
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+
+        lines
+    }
     
     fn apply_image_watermark(
         &self,
@@ -373,11 +1072,28 @@ impl WatermarkRenderer {
         for pixel in watermark_rgba.pixels_mut() {
             pixel[3] = (pixel[3] as f32 * settings.opacity) as u8;
         }
-        
-        image::imageops::overlay(image, &watermark_rgba, x as i64, y as i64);
-        
+
+        Self::overlay_gamma_correct(image, &watermark_rgba, x as i64, y as i64);
+
         Ok(())
     }
+
+    /// Same job as `imageops::overlay`, but blends each logo pixel against
+    /// the background in linear light instead of directly in sRGB, so
+    /// semi-transparent edges don't fringe against bright or dark photos.
+    fn overlay_gamma_correct(image: &mut DynamicImage, overlay: &image::RgbaImage, x: i64, y: i64) {
+        for (ox, oy, pixel) in overlay.enumerate_pixels() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let dst_x = x + ox as i64;
+            let dst_y = y + oy as i64;
+            if dst_x < 0 || dst_y < 0 || dst_x as u32 >= image.width() || dst_y as u32 >= image.height() {
+                continue;
+            }
+            Self::composite_coverage(image, dst_x as u32, dst_y as u32, *pixel, 1.0);
+        }
+    }
     
     fn calculate_text_position(
         &self,
@@ -421,7 +1137,106 @@ impl WatermarkRenderer {
             VerticalAlignment::Center => (img_height - watermark_height) / 2,
             VerticalAlignment::Bottom => img_height - watermark_height - position.margin_y,
         };
-        
+
         (x.max(0), y.max(0))
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One "pixel" per character - deterministic and font-free, so these
+    /// tests exercise the wrapping logic itself rather than glyph metrics.
+    fn measure_chars(s: &str) -> i32 {
+        s.chars().count() as i32
+    }
+
+    #[test]
+    fn wrap_paragraph_without_max_width_keeps_one_line() {
+        let lines = WatermarkRenderer::wrap_paragraph("a long caption here", None, &measure_chars);
+        assert_eq!(lines, vec!["a long caption here".to_string()]);
+    }
+
+    #[test]
+    fn wrap_paragraph_breaks_at_word_boundaries() {
+        let lines = WatermarkRenderer::wrap_paragraph("one two three four", Some(7.0), &measure_chars);
+        assert_eq!(
+            lines,
+            vec!["one two".to_string(), "three".to_string(), "four".to_string()]
+        );
+    }
+
+    #[test]
+    fn wrap_paragraph_hard_breaks_an_oversized_single_word() {
+        let lines = WatermarkRenderer::wrap_paragraph("supercalifragilistic", Some(5.0), &measure_chars);
+        assert_eq!(
+            lines,
+            vec![
+                "super".to_string(),
+                "calif".to_string(),
+                "ragil".to_string(),
+                "istic".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn wrap_paragraph_empty_input_yields_one_empty_line() {
+        assert_eq!(
+            WatermarkRenderer::wrap_paragraph("", Some(10.0), &measure_chars),
+            vec![String::new()]
+        );
+    }
+
+    #[test]
+    fn hard_break_word_splits_at_the_width_limit() {
+        let lines = WatermarkRenderer::hard_break_word("abcdefgh", 3.0, &measure_chars);
+        assert_eq!(lines, vec!["abc".to_string(), "def".to_string(), "gh".to_string()]);
+    }
+
+    #[test]
+    fn hard_break_word_always_keeps_at_least_one_char_per_line() {
+        // An unreasonably small (even zero) max_width must not drop
+        // characters or loop forever - each line gets at least one.
+        let lines = WatermarkRenderer::hard_break_word("ab", 0.0, &measure_chars);
+        assert_eq!(lines, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    fn properties(weight: FkWeight, style: FkStyle) -> Properties {
+        let mut props = Properties::new();
+        props.weight(weight).style(style);
+        props
+    }
+
+    #[test]
+    fn style_matches_treats_oblique_as_italic() {
+        assert!(WatermarkRenderer::style_matches(FkStyle::Italic, FontStyle::Italic));
+        assert!(WatermarkRenderer::style_matches(FkStyle::Oblique, FontStyle::Italic));
+        assert!(!WatermarkRenderer::style_matches(FkStyle::Normal, FontStyle::Italic));
+        assert!(WatermarkRenderer::style_matches(FkStyle::Normal, FontStyle::Normal));
+        assert!(!WatermarkRenderer::style_matches(FkStyle::Italic, FontStyle::Normal));
+    }
+
+    #[test]
+    fn style_distance_is_zero_for_an_exact_match() {
+        let props = properties(FkWeight::BOLD, FkStyle::Italic);
+        let distance = WatermarkRenderer::style_distance(&props, FkWeight::BOLD.0 as u16, FontStyle::Italic);
+        assert_eq!(distance, 0.0);
+    }
+
+    #[test]
+    fn style_distance_penalizes_style_mismatch_more_than_a_modest_weight_gap() {
+        // Bold-upright has the right weight but the wrong style; semibold
+        // italic is 100 units off in weight but has the right style. For a
+        // bold+italic request, style mismatch alone (a 200-unit penalty)
+        // should dominate a weight gap far smaller than that.
+        let bold_upright = properties(FkWeight::BOLD, FkStyle::Normal);
+        let semibold_italic = properties(FkWeight::SEMIBOLD, FkStyle::Italic);
+
+        let distance_bold_upright = WatermarkRenderer::style_distance(&bold_upright, FkWeight::BOLD.0 as u16, FontStyle::Italic);
+        let distance_semibold_italic = WatermarkRenderer::style_distance(&semibold_italic, FkWeight::BOLD.0 as u16, FontStyle::Italic);
+
+        assert!(distance_semibold_italic < distance_bold_upright);
+    }
 }
\ No newline at end of file